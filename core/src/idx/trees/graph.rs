@@ -1,10 +1,89 @@
 use crate::idx::trees::hnsw::ElementId;
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry as HEntry;
-use std::collections::{HashMap, HashSet};
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+/// A candidate neighbor together with its distance to some reference point.
+/// Ordered by distance so it can be used in a min-heap (via `Reverse`) or max-heap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DistElement(f32, ElementId);
+
+impl Eq for DistElement {}
+
+impl PartialOrd for DistElement {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for DistElement {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.0.total_cmp(&other.0).then_with(|| self.1.cmp(&other.1))
+	}
+}
+
+/// A disjoint-set (union-find) structure over [`ElementId`]s, with path
+/// compression and union-by-rank, used by
+/// [`UndirectedGraph::repair_connectivity`] to detect which nodes have
+/// become unreachable from each other.
+struct DisjointSet {
+	parent: HashMap<ElementId, ElementId>,
+	rank: HashMap<ElementId, usize>,
+}
+
+impl DisjointSet {
+	fn new(ids: impl Iterator<Item = ElementId>) -> Self {
+		let mut parent = HashMap::new();
+		let mut rank = HashMap::new();
+		for id in ids {
+			parent.insert(id, id);
+			rank.insert(id, 0);
+		}
+		Self {
+			parent,
+			rank,
+		}
+	}
+
+	fn find(&mut self, x: ElementId) -> ElementId {
+		let p = self.parent[&x];
+		if p == x {
+			return x;
+		}
+		let root = self.find(p);
+		self.parent.insert(x, root);
+		root
+	}
+
+	fn union(&mut self, a: ElementId, b: ElementId) {
+		let ra = self.find(a);
+		let rb = self.find(b);
+		if ra == rb {
+			return;
+		}
+		let rank_a = self.rank[&ra];
+		let rank_b = self.rank[&rb];
+		match rank_a.cmp(&rank_b) {
+			Ordering::Less => {
+				self.parent.insert(ra, rb);
+			}
+			Ordering::Greater => {
+				self.parent.insert(rb, ra);
+			}
+			Ordering::Equal => {
+				self.parent.insert(rb, ra);
+				self.rank.insert(ra, rank_a + 1);
+			}
+		}
+	}
+}
 
 pub(super) struct UndirectedGraph {
 	m_max: usize,
-	nodes: HashMap<ElementId, HashSet<ElementId>>,
+	nodes: HashMap<ElementId, Vec<(ElementId, f32)>>,
 }
 
 impl From<usize> for UndirectedGraph {
@@ -17,80 +96,108 @@ impl From<usize> for UndirectedGraph {
 }
 
 impl UndirectedGraph {
-	pub(super) fn get_edges(&self, node: &ElementId) -> Option<&HashSet<ElementId>> {
+	pub(super) fn get_edges(&self, node: &ElementId) -> Option<&Vec<(ElementId, f32)>> {
 		self.nodes.get(node)
 	}
 
 	#[cfg(test)]
-	pub(super) fn add_edge(&mut self, node1: ElementId, node2: ElementId) {
+	pub(super) fn add_edge(&mut self, node1: ElementId, node2: ElementId, dist: f32) {
+		self.connect(node1, node2, dist);
+	}
+
+	/// Adds a bidirectional edge between `node1` and `node2`, or updates its
+	/// distance if it already exists. A no-op if `node1 == node2`.
+	fn connect(&mut self, node1: ElementId, node2: ElementId, dist: f32) {
 		if node1 != node2 {
-			self.nodes.entry(node1).or_default().insert(node2);
-			self.nodes.entry(node2).or_default().insert(node1);
+			Self::upsert_edge(self.nodes.entry(node1).or_default(), node2, dist);
+			Self::upsert_edge(self.nodes.entry(node2).or_default(), node1, dist);
+		}
+	}
+
+	fn upsert_edge(edges: &mut Vec<(ElementId, f32)>, node: ElementId, dist: f32) {
+		if let Some(e) = edges.iter_mut().find(|(n, _)| *n == node) {
+			e.1 = dist;
+		} else {
+			edges.push((node, dist));
 		}
 	}
 
 	pub(super) fn add_empty_node(&mut self, node: ElementId) -> bool {
 		if let HEntry::Vacant(e) = self.nodes.entry(node) {
-			e.insert(HashSet::with_capacity(self.m_max));
+			e.insert(Vec::with_capacity(self.m_max));
 			true
 		} else {
 			false
 		}
 	}
+
 	pub(super) fn add_node(
 		&mut self,
 		node: ElementId,
-		edges: HashSet<ElementId>,
+		edges: Vec<(ElementId, f32)>,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
 	) -> Option<Vec<ElementId>> {
-		let edges: Vec<ElementId> = if let HEntry::Vacant(e) = self.nodes.entry(node) {
-			e.insert(edges).iter().copied().collect()
+		let edges: Vec<(ElementId, f32)> = edges.into_iter().filter(|(n, _)| *n != node).collect();
+		let edges: Vec<(ElementId, f32)> = if let HEntry::Vacant(e) = self.nodes.entry(node) {
+			e.insert(edges).clone()
 		} else {
 			return None;
 		};
 		// Bidirectional
-		for &e in &edges {
-			self.nodes.entry(e).or_default().insert(node);
+		for &(e, dist) in &edges {
+			Self::upsert_edge(self.nodes.entry(e).or_default(), node, dist);
 		}
-		Some(edges)
+		let ids = edges.into_iter().map(|(e, _)| e).collect();
+		self.trim_to_m_max(node, dist_fn);
+		Some(ids)
 	}
 
-	pub(super) fn set_node(&mut self, node: ElementId, edges: HashSet<ElementId>) {
+	pub(super) fn set_node(
+		&mut self,
+		node: ElementId,
+		edges: Vec<(ElementId, f32)>,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) {
+		let edges: Vec<(ElementId, f32)> = edges.into_iter().filter(|(n, _)| *n != node).collect();
 		let (to_add, to_remove) = match self.nodes.entry(node) {
 			HEntry::Occupied(mut e) => {
 				let old_edges = e.get();
 				let mut to_remove = Vec::with_capacity(1);
-				for old_edge in old_edges {
-					if !edges.contains(old_edge) {
+				for (old_edge, _) in old_edges {
+					if !edges.iter().any(|(n, _)| n == old_edge) {
 						to_remove.push(*old_edge);
 					}
 				}
 				let mut to_add = Vec::with_capacity(1);
-				for new_edge in &edges {
-					if !old_edges.contains(new_edge) {
-						to_add.push(*new_edge);
+				for (new_edge, dist) in &edges {
+					if !old_edges.iter().any(|(n, _)| n == new_edge) {
+						to_add.push((*new_edge, *dist));
 					}
 				}
 				e.insert(edges);
 				(to_add, to_remove)
 			}
 			HEntry::Vacant(e) => {
-				let to_add: Vec<ElementId> = e.insert(edges).iter().copied().collect();
+				let to_add: Vec<(ElementId, f32)> = e.insert(edges).clone();
 				(to_add, vec![])
 			}
 		};
-		for n in to_add {
-			self.nodes.entry(n).or_default().insert(node);
+		for (n, dist) in to_add {
+			Self::upsert_edge(self.nodes.entry(n).or_default(), node, dist);
 		}
 		for n in to_remove {
-			self.nodes.entry(n).or_default().remove(&node);
+			if let Some(edges) = self.nodes.get_mut(&n) {
+				edges.retain(|(e, _)| *e != node);
+			}
 		}
+		self.trim_to_m_max(node, dist_fn);
 	}
 
-	pub(super) fn remove_node(&mut self, node: &ElementId) -> Option<HashSet<ElementId>> {
+	pub(super) fn remove_node(&mut self, node: &ElementId) -> Option<Vec<(ElementId, f32)>> {
 		if let Some(edges) = self.nodes.remove(node) {
-			for edge in &edges {
+			for (edge, _) in &edges {
 				if let Some(edges_to_node) = self.nodes.get_mut(edge) {
-					edges_to_node.remove(node);
+					edges_to_node.retain(|(e, _)| e != node);
 				}
 			}
 			Some(edges)
@@ -98,26 +205,394 @@ impl UndirectedGraph {
 			None
 		}
 	}
+
+	/// Trims the edges of `node` back down to `m_max`, keeping the most diverse
+	/// neighbors according to [`Self::select_neighbors_heuristic`]. `dist_fn`
+	/// must return the real pairwise distance between any two candidates (not
+	/// just ones already connected), otherwise the heuristic degenerates into
+	/// keeping the `m_max` closest neighbors.
+	fn trim_to_m_max(&mut self, node: ElementId, dist_fn: impl Fn(ElementId, ElementId) -> f32) {
+		let Some(edges) = self.nodes.get(&node) else {
+			return;
+		};
+		if edges.len() <= self.m_max {
+			return;
+		}
+		let candidates = edges.clone();
+		let selected = select_neighbors_heuristic(candidates, self.m_max, false, dist_fn);
+		self.nodes.insert(node, selected);
+	}
+
+	/// Detects disconnected components left behind by a batch of
+	/// [`Self::remove_node`] calls and reconnects each one to the component
+	/// containing `entry`, so no part of the graph becomes unreachable from
+	/// the HNSW entry point.
+	///
+	/// Connectivity is computed with a union-find (disjoint-set) structure
+	/// using path compression and union-by-rank: every remaining edge's
+	/// endpoints are unioned, then every node is grouped by the root of its
+	/// component. For each component that doesn't contain `entry`, the pair
+	/// `(orphan_node, main_component_node)` with the smallest `dist_fn`
+	/// distance is connected with a repair edge. Returns the repair edges that
+	/// were added.
+	pub(super) fn repair_connectivity(
+		&mut self,
+		entry: ElementId,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) -> Vec<(ElementId, ElementId)> {
+		let mut dsu = self.build_dsu(entry);
+
+		let main_root = dsu.find(entry);
+		let mut components: HashMap<ElementId, Vec<ElementId>> = HashMap::new();
+		for node in self.nodes.keys().copied().collect::<Vec<_>>() {
+			let root = dsu.find(node);
+			components.entry(root).or_default().push(node);
+		}
+		let main_component = components.remove(&main_root).unwrap_or_default();
+
+		let mut repairs = Vec::new();
+		if main_component.is_empty() {
+			return repairs;
+		}
+		for (_, orphan_nodes) in components {
+			let mut closest: Option<(ElementId, ElementId, f32)> = None;
+			for &orphan in &orphan_nodes {
+				for &main in &main_component {
+					let d = dist_fn(orphan, main);
+					if closest.is_none_or(|(_, _, best)| d < best) {
+						closest = Some((orphan, main, d));
+					}
+				}
+			}
+			if let Some((orphan, main, d)) = closest {
+				self.connect(orphan, main, d);
+				repairs.push((orphan, main));
+			}
+		}
+		repairs
+	}
+
+	/// Builds a [`DisjointSet`] over every node plus `entry` (in case `entry`
+	/// isn't currently a live node), unioned along every remaining edge.
+	/// Shared by [`Self::repair_connectivity`] and
+	/// [`Self::connected_components_count`].
+	fn build_dsu(&self, entry: ElementId) -> DisjointSet {
+		let mut dsu = DisjointSet::new(self.nodes.keys().copied().chain(std::iter::once(entry)));
+		for (&node, edges) in &self.nodes {
+			for &(neighbor, _) in edges {
+				dsu.union(node, neighbor);
+			}
+		}
+		dsu
+	}
+
+	/// Builds an immutable, cache-friendly snapshot of the graph, to be used for
+	/// read-heavy query-time traversal once the graph (or a layer of it) has
+	/// stabilized. See [`FrozenGraph`].
+	pub(super) fn freeze(&self) -> FrozenGraph {
+		FrozenGraph::from(self)
+	}
+
+	/// Buckets every node by its neighbor count, giving a histogram of
+	/// `degree -> number of nodes with that degree`. Useful to spot
+	/// over-pruned hubs or nodes saturating `m_max`.
+	pub(super) fn degree_distribution(&self) -> HashMap<usize, usize> {
+		let mut distribution = HashMap::new();
+		for edges in self.nodes.values() {
+			*distribution.entry(edges.len()).or_insert(0) += 1;
+		}
+		distribution
+	}
+
+	/// Returns the nodes with zero edges, e.g. left over from
+	/// [`Self::add_empty_node`] that never got connected.
+	pub(super) fn isolated_nodes(&self) -> Vec<ElementId> {
+		self.nodes.iter().filter(|(_, edges)| edges.is_empty()).map(|(&n, _)| n).collect()
+	}
+
+	/// Counts the number of connected components in the graph, including the
+	/// one containing `entry`. A healthy HNSW graph has a single component;
+	/// more than one means part of the index is unreachable from the entry
+	/// point. See also [`Self::repair_connectivity`].
+	pub(super) fn connected_components_count(&self, entry: ElementId) -> usize {
+		let mut dsu = self.build_dsu(entry);
+		let mut roots: HashSet<ElementId> = self.nodes.keys().map(|&n| dsu.find(n)).collect();
+		roots.insert(dsu.find(entry));
+		roots.len()
+	}
+
+	/// See the free function of the same name.
+	pub(super) fn select_neighbors_heuristic(
+		&self,
+		_query: ElementId,
+		candidates: Vec<(ElementId, f32)>,
+		m: usize,
+		keep_pruned: bool,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) -> Vec<(ElementId, f32)> {
+		select_neighbors_heuristic(candidates, m, keep_pruned, dist_fn)
+	}
+}
+
+/// Implements the HNSW "heuristic" neighbor selection (algorithm 4 in the
+/// Malkov & Yashunin paper): rather than keeping the `m` closest candidates
+/// to the query (which tends to cluster neighbors in the same direction), it
+/// greedily keeps a candidate only if it is strictly closer to the query than
+/// to every neighbor already selected, favouring geometric diversity.
+///
+/// If `keep_pruned` is set, once `R` stops growing the discarded candidates
+/// are used to pad `R` back up to `m` elements.
+///
+/// `dist_fn` must be able to return the distance between any two candidates,
+/// not just ones that happen to already be connected in a graph.
+fn select_neighbors_heuristic(
+	candidates: Vec<(ElementId, f32)>,
+	m: usize,
+	keep_pruned: bool,
+	dist_fn: impl Fn(ElementId, ElementId) -> f32,
+) -> Vec<(ElementId, f32)> {
+	let mut w: BinaryHeap<std::cmp::Reverse<DistElement>> =
+		candidates.into_iter().map(|(e, d)| std::cmp::Reverse(DistElement(d, e))).collect();
+	let mut r: Vec<(ElementId, f32)> = Vec::with_capacity(m);
+	let mut pruned: Vec<(ElementId, f32)> = Vec::new();
+	while let Some(std::cmp::Reverse(DistElement(dist_to_query, e))) = w.pop() {
+		if r.len() >= m {
+			break;
+		}
+		let is_diverse = r.iter().all(|&(r_elem, _)| dist_to_query < dist_fn(e, r_elem));
+		if is_diverse {
+			r.push((e, dist_to_query));
+		} else if keep_pruned {
+			pruned.push((e, dist_to_query));
+		}
+	}
+	if keep_pruned {
+		pruned.sort_by(|a, b| a.1.total_cmp(&b.1));
+		for p in pruned {
+			if r.len() >= m {
+				break;
+			}
+			r.push(p);
+		}
+	}
+	r
+}
+
+/// An immutable, compressed-sparse-row snapshot of an [`UndirectedGraph`]:
+/// a sorted node array plus offsets into a flat neighbor array, for
+/// contiguous, cache-friendly traversal during search. Rebuilt once the
+/// mutable `UndirectedGraph` it was built from has stabilized.
+pub(super) struct FrozenGraph {
+	node_ids: Vec<ElementId>,
+	offsets: Vec<usize>,
+	neighbors: Vec<ElementId>,
+}
+
+impl From<&UndirectedGraph> for FrozenGraph {
+	fn from(g: &UndirectedGraph) -> Self {
+		let mut node_ids: Vec<ElementId> = g.nodes.keys().copied().collect();
+		node_ids.sort_unstable();
+
+		let mut offsets = Vec::with_capacity(node_ids.len() + 1);
+		let mut neighbors = Vec::new();
+		offsets.push(0);
+		for node in &node_ids {
+			if let Some(edges) = g.nodes.get(node) {
+				neighbors.extend(edges.iter().map(|(e, _)| *e));
+			}
+			offsets.push(neighbors.len());
+		}
+		Self {
+			node_ids,
+			offsets,
+			neighbors,
+		}
+	}
+}
+
+impl FrozenGraph {
+	/// Returns the neighbors of `node`, or `None` if `node` is not present in
+	/// the snapshot.
+	pub(super) fn get_edges(&self, node: &ElementId) -> Option<&[ElementId]> {
+		let idx = self.node_ids.binary_search(node).ok()?;
+		Some(&self.neighbors[self.offsets[idx]..self.offsets[idx + 1]])
+	}
+}
+
+/// The edges of a single node in a [`ConcurrentGraph`] snapshot. Wrapped in an
+/// `Arc` so that untouched nodes can be shared between successive versions
+/// instead of being deep-copied on every write.
+type ConcurrentEdges = Arc<Vec<(ElementId, f32)>>;
+
+/// A snapshot of a [`ConcurrentGraph`]: an immutable, point-in-time view of
+/// every node's adjacency.
+pub(super) type GraphSnapshot = Arc<HashMap<ElementId, ConcurrentEdges>>;
+
+/// A copy-on-write, concurrently-readable variant of [`UndirectedGraph`].
+/// Readers call [`Self::snapshot`] for a consistent `Arc` they can traverse
+/// without ever blocking the writer; the writer clones the root, mutates the
+/// clone and swaps it in, so nodes it doesn't touch stay shared.
+pub(super) struct ConcurrentGraph {
+	m_max: usize,
+	root: RwLock<GraphSnapshot>,
+}
+
+impl From<usize> for ConcurrentGraph {
+	fn from(m_max: usize) -> Self {
+		Self {
+			m_max,
+			root: RwLock::new(Arc::new(HashMap::new())),
+		}
+	}
+}
+
+impl ConcurrentGraph {
+	/// Returns a consistent, immutable snapshot of the graph. The snapshot is
+	/// unaffected by any writes that commit after it was taken.
+	pub(super) fn snapshot(&self) -> GraphSnapshot {
+		self.root.read().unwrap().clone()
+	}
+
+	/// Runs `f` against a clone of the current root and commits the result as
+	/// the new root. Only one writer is expected to call this at a time.
+	fn commit(&self, f: impl FnOnce(&mut HashMap<ElementId, ConcurrentEdges>)) {
+		let mut guard = self.root.write().unwrap();
+		let mut new_root: HashMap<ElementId, ConcurrentEdges> = (**guard).clone();
+		f(&mut new_root);
+		*guard = Arc::new(new_root);
+	}
+
+	pub(super) fn add_empty_node(&self, node: ElementId) -> bool {
+		let mut added = false;
+		self.commit(|nodes| {
+			if let HEntry::Vacant(e) = nodes.entry(node) {
+				e.insert(Arc::new(Vec::with_capacity(self.m_max)));
+				added = true;
+			}
+		});
+		added
+	}
+
+	pub(super) fn add_node(
+		&self,
+		node: ElementId,
+		edges: Vec<(ElementId, f32)>,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) -> Option<Vec<ElementId>> {
+		let edges: Vec<(ElementId, f32)> = edges.into_iter().filter(|(n, _)| *n != node).collect();
+		let mut ids = None;
+		self.commit(|nodes| {
+			if let HEntry::Vacant(e) = nodes.entry(node) {
+				e.insert(Arc::new(edges.clone()));
+				ids = Some(edges.iter().map(|(e, _)| *e).collect());
+				for (n, dist) in &edges {
+					let existing = nodes.entry(*n).or_default();
+					let mut v = (**existing).clone();
+					if !v.iter().any(|(e, _)| e == &node) {
+						v.push((node, *dist));
+					}
+					*existing = Arc::new(v);
+				}
+				Self::trim_to_m_max(nodes, node, self.m_max, &dist_fn);
+			}
+		});
+		ids
+	}
+
+	pub(super) fn set_node(
+		&self,
+		node: ElementId,
+		edges: Vec<(ElementId, f32)>,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) {
+		let edges: Vec<(ElementId, f32)> = edges.into_iter().filter(|(n, _)| *n != node).collect();
+		self.commit(|nodes| {
+			let old_edges = nodes.get(&node).cloned().unwrap_or_default();
+			for (old_edge, _) in old_edges.iter() {
+				if edges.iter().any(|(n, _)| n == old_edge) {
+					continue;
+				}
+				if let Some(e) = nodes.get(old_edge) {
+					let mut v = (**e).clone();
+					v.retain(|(n, _)| *n != node);
+					nodes.insert(*old_edge, Arc::new(v));
+				}
+			}
+			for (new_edge, dist) in &edges {
+				if !old_edges.iter().any(|(n, _)| n == new_edge) {
+					let existing = nodes.entry(*new_edge).or_default();
+					let mut v = (**existing).clone();
+					v.push((node, *dist));
+					*existing = Arc::new(v);
+				}
+			}
+			nodes.insert(node, Arc::new(edges));
+			Self::trim_to_m_max(nodes, node, self.m_max, &dist_fn);
+		});
+	}
+
+	/// Same contract as [`UndirectedGraph::trim_to_m_max`]:
+	/// caps `node`'s edges at `m_max`, keeping the most diverse neighbors.
+	fn trim_to_m_max(
+		nodes: &mut HashMap<ElementId, ConcurrentEdges>,
+		node: ElementId,
+		m_max: usize,
+		dist_fn: impl Fn(ElementId, ElementId) -> f32,
+	) {
+		let Some(edges) = nodes.get(&node) else {
+			return;
+		};
+		if edges.len() <= m_max {
+			return;
+		}
+		let candidates = (**edges).clone();
+		let selected = select_neighbors_heuristic(candidates, m_max, false, dist_fn);
+		nodes.insert(node, Arc::new(selected));
+	}
+
+	pub(super) fn remove_node(&self, node: ElementId) -> Option<Vec<(ElementId, f32)>> {
+		let mut removed = None;
+		self.commit(|nodes| {
+			if let Some(edges) = nodes.remove(&node) {
+				for (edge, _) in edges.iter() {
+					if let Some(e) = nodes.get(edge) {
+						let mut v = (**e).clone();
+						v.retain(|(n, _)| n != &node);
+						nodes.insert(*edge, Arc::new(v));
+					}
+				}
+				removed = Some((*edges).clone());
+			}
+		});
+		removed
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use crate::idx::trees::graph::UndirectedGraph;
 	use crate::idx::trees::hnsw::ElementId;
-	use std::collections::{HashMap, HashSet};
+	use std::collections::HashSet;
 
 	impl UndirectedGraph {
 		pub(in crate::idx::trees) fn len(&self) -> usize {
 			self.nodes.len()
 		}
 
-		pub(in crate::idx::trees) fn nodes(&self) -> &HashMap<ElementId, HashSet<ElementId>> {
+		pub(in crate::idx::trees) fn nodes(
+			&self,
+		) -> &std::collections::HashMap<ElementId, Vec<(ElementId, f32)>> {
 			&self.nodes
 		}
+
 		pub(in crate::idx::trees) fn check(&self, g: Vec<(ElementId, Vec<ElementId>)>) {
 			for (n, e) in g {
-				let edges: HashSet<ElementId> = e.into_iter().collect();
-				assert_eq!(self.get_edges(&n), Some(&edges), "{n}");
+				let expected: HashSet<ElementId> = e.into_iter().collect();
+				let actual: HashSet<ElementId> = self
+					.get_edges(&n)
+					.map(|edges| edges.iter().map(|(e, _)| *e).collect())
+					.unwrap_or_default();
+				assert_eq!(actual, expected, "{n}");
 			}
 		}
 	}
@@ -139,17 +614,17 @@ mod tests {
 		g.check(vec![(0, vec![])]);
 
 		// Adding a node with one edge
-		let res = g.add_node(1, HashSet::from([0]));
+		let res = g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
 		assert_eq!(res, Some(vec![0]));
 		g.check(vec![(0, vec![1]), (1, vec![0])]);
 
 		// Adding the same node
-		let res = g.add_node(1, HashSet::from([2]));
+		let res = g.add_node(1, vec![(2, 1.0)], |_, _| 1.0);
 		assert_eq!(res, None);
 		g.check(vec![(0, vec![1]), (1, vec![0])]);
 
 		// Adding a node with two edges
-		let res = g.add_node(2, HashSet::from([0, 1]));
+		let res = g.add_node(2, vec![(0, 1.0), (1, 1.0)], |_, _| 1.0);
 		assert_eq!(
 			res.map(|mut v| {
 				v.sort();
@@ -160,7 +635,7 @@ mod tests {
 		g.check(vec![(0, vec![1, 2]), (1, vec![0, 2]), (2, vec![0, 1])]);
 
 		// Adding a node with two edges
-		let res = g.add_node(3, HashSet::from([1, 2]));
+		let res = g.add_node(3, vec![(1, 1.0), (2, 1.0)], |_, _| 1.0);
 		assert_eq!(
 			res.map(|mut v| {
 				v.sort();
@@ -171,18 +646,18 @@ mod tests {
 		g.check(vec![(0, vec![1, 2]), (1, vec![0, 2, 3]), (2, vec![0, 1, 3]), (3, vec![1, 2])]);
 
 		// Change the edges of a node
-		g.set_node(3, HashSet::from([0]));
+		g.set_node(3, vec![(0, 1.0)], |_, _| 1.0);
 		g.check(vec![(0, vec![1, 2, 3]), (1, vec![0, 2]), (2, vec![0, 1]), (3, vec![0])]);
 
 		// Add an edge
-		g.add_edge(2, 3);
+		g.add_edge(2, 3, 1.0);
 		g.check(vec![(0, vec![1, 2, 3]), (1, vec![0, 2]), (2, vec![0, 1, 3]), (3, vec![0, 2])]);
 
 		// Remove a node
 		let res = g.remove_node(&2);
 		assert_eq!(
 			res.map(|v| {
-				let mut v: Vec<ElementId> = v.into_iter().collect();
+				let mut v: Vec<ElementId> = v.into_iter().map(|(e, _)| e).collect();
 				v.sort();
 				v
 			}),
@@ -195,7 +670,216 @@ mod tests {
 		assert_eq!(res, None);
 
 		// Set a non existing node
-		g.set_node(2, HashSet::from([1]));
+		g.set_node(2, vec![(1, 1.0)], |_, _| 1.0);
 		g.check(vec![(0, vec![1, 3]), (1, vec![0, 2]), (2, vec![1]), (3, vec![0])]);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn test_select_neighbors_heuristic() {
+		let g: UndirectedGraph = 10.into();
+		// Three candidates clustered close together, and one further but diverse.
+		let candidates = vec![(1, 1.0), (2, 1.1), (3, 1.2), (4, 5.0)];
+		let selected = g.select_neighbors_heuristic(0, candidates, 2, false, |a, b| {
+			// Candidates 1, 2 and 3 are mutually close; 4 is far from everything.
+			match (a.min(b), a.max(b)) {
+				(1, 2) | (1, 3) | (2, 3) => 0.1,
+				_ => 10.0,
+			}
+		});
+		let ids: HashSet<ElementId> = selected.into_iter().map(|(e, _)| e).collect();
+		assert_eq!(ids, HashSet::from([1, 4]));
+	}
+
+	/// Real, position-based pairwise distance for the trim tests below: 1, 2
+	/// and 3 cluster just past the query on one side, while 4 sits on the
+	/// opposite side, so it is genuinely diverse rather than merely further
+	/// away along the same direction.
+	fn line_dist(a: ElementId, b: ElementId) -> f32 {
+		fn pos(id: ElementId) -> f32 {
+			match id {
+				1 => 1.0,
+				2 => 1.05,
+				3 => 1.1,
+				4 => -5.0,
+				_ => 0.0,
+			}
+		}
+		(pos(a) - pos(b)).abs()
+	}
+
+	#[test]
+	fn test_add_node_trims_to_m_max() {
+		let mut g: UndirectedGraph = 2.into();
+		// 1, 2 and 3 cluster together; 4 is on the opposite side and genuinely
+		// diverse. The heuristic should keep one of the close cluster plus 4,
+		// not just the 2 closest (1 and 2).
+		g.add_node(0, vec![(1, 1.0), (2, 1.05), (3, 1.1), (4, 5.0)], line_dist);
+		let edges: HashSet<ElementId> =
+			g.get_edges(&0).map(|e| e.iter().map(|(e, _)| *e).collect()).unwrap_or_default();
+		assert_eq!(edges, HashSet::from([1, 4]));
+	}
+
+	#[test]
+	fn test_set_node_trims_to_m_max() {
+		let mut g: UndirectedGraph = 2.into();
+		g.add_empty_node(0);
+		g.set_node(0, vec![(1, 1.0), (2, 1.05), (3, 1.1), (4, 5.0)], line_dist);
+		let edges: HashSet<ElementId> =
+			g.get_edges(&0).map(|e| e.iter().map(|(e, _)| *e).collect()).unwrap_or_default();
+		assert_eq!(edges, HashSet::from([1, 4]));
+	}
+
+	#[test]
+	fn test_add_node_and_set_node_ignore_self_loops() {
+		let mut g: UndirectedGraph = 10.into();
+		g.add_node(0, vec![(0, 1.0), (1, 1.0)], |_, _| 1.0);
+		g.check(vec![(0, vec![1]), (1, vec![0])]);
+
+		g.set_node(0, vec![(0, 1.0)], |_, _| 1.0);
+		g.check(vec![(0, vec![]), (1, vec![])]);
+	}
+
+	#[test]
+	fn test_repair_connectivity() {
+		let mut g: UndirectedGraph = 10.into();
+		// Main component: 0 - 1 - 2, entry point is 0.
+		g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
+		g.add_node(2, vec![(1, 1.0)], |_, _| 1.0);
+		// Orphaned component, disconnected from the main one: 10 - 11.
+		g.add_node(11, vec![(10, 1.0)], |_, _| 1.0);
+
+		let repairs = g.repair_connectivity(0, |a, b| {
+			// 11 is the closest orphan node to the main component (to node 2).
+			if (a, b) == (11, 2) || (a, b) == (2, 11) {
+				0.5
+			} else {
+				100.0
+			}
+		});
+
+		assert_eq!(repairs, vec![(11, 2)]);
+		g.check(vec![(0, vec![1]), (1, vec![0, 2]), (2, vec![1, 11]), (10, vec![11]), (11, vec![10, 2])]);
+
+		// Already fully connected: no repairs needed.
+		let repairs = g.repair_connectivity(0, |_, _| 1.0);
+		assert!(repairs.is_empty());
+	}
+
+	#[test]
+	fn test_repair_connectivity_missing_entry() {
+		// `entry` isn't a node in the graph at all (e.g. it was just removed).
+		// This must not panic, and since there's no main component to
+		// reconnect anything to, no repairs should be made.
+		let mut g: UndirectedGraph = 10.into();
+		g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
+
+		let repairs = g.repair_connectivity(42, |_, _| 1.0);
+		assert!(repairs.is_empty());
+	}
+
+	#[test]
+	fn test_graph_stats() {
+		let mut g: UndirectedGraph = 10.into();
+		// Main component: 0 - 1 - 2.
+		g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
+		g.add_node(2, vec![(1, 1.0)], |_, _| 1.0);
+		// A lone node left over from add_empty_node.
+		g.add_empty_node(3);
+		// A second, disconnected component.
+		g.add_node(11, vec![(10, 1.0)], |_, _| 1.0);
+
+		let distribution = g.degree_distribution();
+		// Degree 0: node 3. Degree 1: nodes 0, 2, 10, 11. Degree 2: node 1.
+		assert_eq!(distribution.get(&0), Some(&1));
+		assert_eq!(distribution.get(&1), Some(&4));
+		assert_eq!(distribution.get(&2), Some(&1));
+
+		let mut isolated = g.isolated_nodes();
+		isolated.sort();
+		assert_eq!(isolated, vec![3]);
+
+		assert_eq!(g.connected_components_count(0), 3);
+	}
+
+	#[test]
+	fn test_freeze() {
+		let mut g: UndirectedGraph = 10.into();
+		g.add_empty_node(0);
+		g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
+		g.add_node(2, vec![(0, 1.0), (1, 1.0)], |_, _| 1.0);
+
+		let frozen = g.freeze();
+		for node in [0, 1, 2] {
+			let expected: HashSet<ElementId> =
+				g.get_edges(&node).map(|e| e.iter().map(|(e, _)| *e).collect()).unwrap_or_default();
+			let actual: HashSet<ElementId> =
+				frozen.get_edges(&node).map(|e| e.iter().copied().collect()).unwrap_or_default();
+			assert_eq!(actual, expected, "{node}");
+		}
+		assert_eq!(frozen.get_edges(&42), None);
+	}
+
+	fn edges_of(snapshot: &super::GraphSnapshot, node: &ElementId) -> HashSet<ElementId> {
+		snapshot.get(node).map(|e| e.iter().map(|(e, _)| *e).collect()).unwrap_or_default()
+	}
+
+	#[test]
+	fn test_concurrent_graph() {
+		let g = super::ConcurrentGraph::from(10);
+
+		assert!(g.add_empty_node(0));
+		assert!(!g.add_empty_node(0));
+
+		// A reader takes a snapshot before the next write...
+		let before = g.snapshot();
+		assert_eq!(edges_of(&before, &0), HashSet::new());
+
+		let res = g.add_node(1, vec![(0, 1.0)], |_, _| 1.0);
+		assert_eq!(res, Some(vec![0]));
+
+		// ...and keeps seeing the old, pre-write state even after the commit.
+		assert_eq!(edges_of(&before, &0), HashSet::new());
+		let after = g.snapshot();
+		assert_eq!(edges_of(&after, &0), HashSet::from([1]));
+		assert_eq!(edges_of(&after, &1), HashSet::from([0]));
+
+		g.set_node(1, vec![], |_, _| 1.0);
+		let after_unset = g.snapshot();
+		assert_eq!(edges_of(&after_unset, &0), HashSet::new());
+		assert_eq!(edges_of(&after_unset, &1), HashSet::new());
+
+		let res = g.add_node(2, vec![(0, 1.0), (1, 1.0)], |_, _| 1.0);
+		assert_eq!(res.map(|mut v| (v.sort(), v).1), Some(vec![0, 1]));
+
+		let removed = g.remove_node(2);
+		assert_eq!(
+			removed.map(|mut v| {
+				v.sort_by_key(|(e, _)| *e);
+				v
+			}),
+			Some(vec![(0, 1.0), (1, 1.0)])
+		);
+		let after_remove = g.snapshot();
+		assert_eq!(edges_of(&after_remove, &0), HashSet::new());
+		assert_eq!(edges_of(&after_remove, &1), HashSet::new());
+	}
+
+	#[test]
+	fn test_concurrent_graph_ignores_self_loops() {
+		let g = super::ConcurrentGraph::from(10);
+		g.add_node(0, vec![(0, 1.0), (1, 1.0)], |_, _| 1.0);
+		assert_eq!(edges_of(&g.snapshot(), &0), HashSet::from([1]));
+
+		g.set_node(0, vec![(0, 1.0)], |_, _| 1.0);
+		assert_eq!(edges_of(&g.snapshot(), &0), HashSet::new());
+	}
+
+	#[test]
+	fn test_concurrent_graph_trims_to_m_max() {
+		let g = super::ConcurrentGraph::from(2);
+		// Same layout as UndirectedGraph's trim tests: 1, 2 and 3 cluster
+		// together, 4 is on the opposite side and genuinely diverse.
+		g.add_node(0, vec![(1, 1.0), (2, 1.05), (3, 1.1), (4, 5.0)], line_dist);
+		assert_eq!(edges_of(&g.snapshot(), &0), HashSet::from([1, 4]));
+	}
+}